@@ -1,58 +1,214 @@
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::{env, near_bindgen, AccountId};
-use near_sdk::collections::{LookupSet, UnorderedSet, UnorderedMap};
+use near_sdk::json_types::U128;
+use near_sdk::{env, ext_contract, is_promise_success, near_bindgen, AccountId, Gas, PromiseOrValue};
+use near_sdk::collections::{UnorderedSet, UnorderedMap};
 
 #[global_allocator]
 static ALLOC: near_sdk::wee_alloc::WeeAlloc = near_sdk::wee_alloc::WeeAlloc::INIT;
 
-#[derive(BorshDeserialize, BorshSerialize)]
+const GAS_FOR_FT_TRANSFER: Gas = 5_000_000_000_000;
+const GAS_FOR_TRANSFER_RESOLVE: Gas = 5_000_000_000_000;
+const GAS_FOR_NFT_TRANSFER: Gas = 5_000_000_000_000;
+
+// the NEP-141 token contract backing an escrowed fungible asset
+#[ext_contract(ext_ft)]
+pub trait FungibleToken {
+    fn ft_transfer(&mut self, receiver_id: AccountId, amount: U128, memo: Option<String>);
+    fn ft_transfer_call(&mut self, receiver_id: AccountId, amount: U128, memo: Option<String>, msg: String) -> PromiseOrValue<U128>;
+    fn ft_balance_of(&self, account_id: AccountId) -> U128;
+}
+
+// the NEP-171 token contract backing an escrowed non-fungible asset
+#[ext_contract(ext_nft)]
+pub trait NonFungibleToken {
+    fn nft_transfer(&mut self, receiver_id: AccountId, token_id: String, approval_id: Option<u64>, memo: Option<String>);
+    fn nft_transfer_call(&mut self, receiver_id: AccountId, token_id: String, approval_id: Option<u64>, memo: Option<String>, msg: String) -> PromiseOrValue<bool>;
+}
+
+#[ext_contract(ext_self)]
+pub trait ExtSelf {
+    fn on_transfer_complete(&mut self, tx_name: String) -> bool;
+}
+
+// either a quantity of a NEP-141 token or one specific NEP-171 token
+#[derive(BorshDeserialize, BorshSerialize, Clone, PartialEq)]
+pub enum AssetKind {
+    Fungible { token_id: AccountId, amount: U128 },
+    NonFungible { nft_contract: AccountId, token_id: String }
+}
+
+impl AssetKind {
+    fn transfer(&self, receiver: AccountId) -> near_sdk::Promise {
+        match self {
+            AssetKind::Fungible { token_id, amount } =>
+                ext_ft::ft_transfer(receiver, *amount, None, token_id.clone(), 1, GAS_FOR_FT_TRANSFER),
+            AssetKind::NonFungible { nft_contract, token_id } =>
+                ext_nft::nft_transfer(receiver, token_id.clone(), None, None, nft_contract.clone(), 1, GAS_FOR_NFT_TRANSFER)
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            AssetKind::Fungible { token_id, amount } => format!("{} of {}", amount.0, token_id),
+            AssetKind::NonFungible { nft_contract, token_id } => format!("{} from {}", token_id, nft_contract)
+        }
+    }
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Clone, PartialEq)]
 pub struct Asset {
-    name: String,
-    quantity: f64
+    kind: AssetKind
 }
 
-#[derive(BorshDeserialize, BorshSerialize)]
+// who gets the asset once a ReleasePlan resolves down to this leaf
+#[derive(BorshDeserialize, BorshSerialize, Clone, PartialEq)]
+pub struct Payment {
+    receiver: AccountId
+}
+
+// how many owners have signed off, out of how many are needed
+#[derive(BorshDeserialize, BorshSerialize, Clone, PartialEq)]
+pub struct ApprovalStatus {
+    signed: u32,
+    threshold: u32
+}
+
+// a fact that can unlock a branch of a ReleasePlan
+#[derive(BorshDeserialize, BorshSerialize, Clone, PartialEq)]
+pub enum Condition {
+    Timestamp(u64),
+    Signature(AccountId)
+}
+
+// proof that a Condition has been met, handed to Transaction::apply_witness
+pub enum Witness {
+    Timestamp(u64),
+    Signature(AccountId)
+}
+
+fn condition_met(condition: &Condition, witness: &Witness) -> bool {
+    match (condition, witness) {
+        (Condition::Timestamp(deadline), Witness::Timestamp(now)) => now >= deadline,
+        (Condition::Signature(expected), Witness::Signature(signer)) => expected == signer,
+        _ => false
+    }
+}
+
+// a small expression tree describing the conditions under which a transaction's
+// asset is released. apply_witness collapses whichever node the witness
+// satisfies; once it reduces to a single Pay the transaction is executable.
+#[derive(BorshDeserialize, BorshSerialize, Clone, PartialEq)]
+pub enum ReleasePlan {
+    Pay(Payment),
+    After(Condition, Box<ReleasePlan>),
+    Or(Box<ReleasePlan>, Box<ReleasePlan>),
+    And(Box<ReleasePlan>, Box<ReleasePlan>)
+}
+
+impl ReleasePlan {
+    fn reduce(&self, witness: &Witness) -> Self {
+        match self {
+            ReleasePlan::Pay(payment) => ReleasePlan::Pay(payment.clone()),
+            ReleasePlan::After(condition, inner) => {
+                if condition_met(condition, witness) {
+                    inner.reduce(witness)
+                } else {
+                    ReleasePlan::After(condition.clone(), Box::new(inner.reduce(witness)))
+                }
+            },
+            ReleasePlan::And(left, right) => {
+                let left = left.reduce(witness);
+                let right = right.reduce(witness);
+                match (&left, &right) {
+                    (ReleasePlan::Pay(payment), ReleasePlan::Pay(_)) => ReleasePlan::Pay(payment.clone()),
+                    _ => ReleasePlan::And(Box::new(left), Box::new(right))
+                }
+            },
+            ReleasePlan::Or(left, right) => {
+                let left = left.reduce(witness);
+                if let ReleasePlan::Pay(_) = left {
+                    return left;
+                }
+                let right = right.reduce(witness);
+                if let ReleasePlan::Pay(_) = right {
+                    return right;
+                }
+                ReleasePlan::Or(Box::new(left), Box::new(right))
+            }
+        }
+    }
+
+    fn is_settled(&self) -> bool {
+        matches!(self, ReleasePlan::Pay(_))
+    }
+
+    fn payment(&self) -> Option<&Payment> {
+        match self {
+            ReleasePlan::Pay(payment) => Some(payment),
+            _ => None
+        }
+    }
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Clone)]
 pub struct Transaction {
     sender : AccountId,
-    receiver : AccountId,
     asset : Asset,
-    satisfied : bool
+    plan : ReleasePlan,
+    deposited : bool,
+    deadline : u64
 }
 
 impl PartialEq for Transaction {
     fn eq(&self, other: &Self) -> bool {
         self.sender == other.sender &&
-        self.receiver == other.receiver &&
-        self.asset.name == other.asset.name &&
-        self.asset.quantity == other.asset.quantity &&
-        self.satisfied == other.satisfied
+        self.asset == other.asset &&
+        self.plan == other.plan &&
+        self.deposited == other.deposited &&
+        self.deadline == other.deadline
     }
 }
 
 impl Transaction {
 
-    pub fn new(sender: AccountId, receiver: AccountId, asset: Asset) -> Self {
-        let satisfied = false;
+    pub fn new(sender: AccountId, asset: Asset, plan: ReleasePlan, deadline: u64) -> Self {
         Self {
             sender,
-            receiver,
             asset,
-            satisfied
+            plan,
+            deposited: false,
+            deadline
         }
     }
 
-    pub fn toggle_satisfied(&mut self) {
-        self.satisfied = !self.satisfied;
+    pub fn toggle_deposited(&mut self) {
+        self.deposited = !self.deposited;
+    }
+
+    // walk the release plan, collapsing whatever the witness satisfies
+    pub fn apply_witness(&mut self, witness: Witness) {
+        self.plan = self.plan.reduce(&witness);
+    }
+
+    pub fn is_executable(&self) -> bool {
+        self.plan.is_settled()
+    }
+
+    // settled release conditions alone aren't enough to pay out; the asset
+    // also has to have actually been deposited into escrow
+    pub fn is_payable(&self) -> bool {
+        self.is_executable() && self.deposited
     }
 }
 
 #[near_bindgen]
 #[derive(BorshDeserialize, BorshSerialize)]
 pub struct Contract {
-    assets: UnorderedMap<AccountId, Asset>,
     transactions: UnorderedMap<String, Transaction>,
     owners: UnorderedSet<AccountId>,
-    signatures : LookupSet<AccountId>,
+    signatures : UnorderedSet<AccountId>,
+    cancelled: bool,
+    threshold: u32,
 }
 
 impl Default for Contract {
@@ -65,33 +221,26 @@ impl Default for Contract {
 impl Contract {
 
     #[init]
-    pub fn new(owners_in: Vec<AccountId>) -> Self {
+    pub fn new(owners_in: Vec<AccountId>, threshold: u32) -> Self {
         let mut owners = UnorderedSet::new(b"o".to_vec());
         for acct in owners_in.iter() {
             owners.insert(acct);
         }
+        assert!(threshold > 0 && u64::from(threshold) <= owners.len(), "threshold must be between 1 and the number of owners");
         Self {
-            assets: UnorderedMap::new(b"a".to_vec()),
             transactions: UnorderedMap::new(b"t".to_vec()),
             owners,
-            signatures: LookupSet::new(b"s".to_vec()),
+            signatures: UnorderedSet::new(b"s".to_vec()),
+            cancelled: false,
+            threshold,
         }
     }
 
-    pub fn add_tx(&mut self, tx_name: String, sender: AccountId, receiver: AccountId, asset_type: String, quantity: f64) {
+    pub fn add_tx(&mut self, tx_name: String, sender: AccountId, kind: AssetKind, plan: ReleasePlan, deadline: u64) {
         self.assert_owner();
         self.assert_no_agreement();
-        let ass = Asset {
-            name: asset_type,
-            quantity: quantity
-        };
-
-        let tx = Transaction { 
-            sender : sender,
-            receiver : receiver,
-            asset : ass,
-            satisfied : false
-        };
+        let ass = Asset { kind };
+        let tx = Transaction::new(sender, ass, plan, deadline);
         self.transactions.insert(&tx_name, &tx);
     }
 
@@ -105,72 +254,174 @@ impl Contract {
         self.transactions.get(&tx_name).unwrap()
     }
 
-    pub fn dep_asset(&mut self, asset: Asset, tx_name: String) {
-        // TODO: cross contract to senders tokens to see if they got it
+    // a third party's signature satisfies any Signature condition in the plan;
+    // the witness is always the caller, never a caller-supplied identity
+    pub fn apply_signature_witness(&mut self, tx_name: String) {
+        let mut tx = self.transactions.get(&tx_name).unwrap();
+        tx.apply_witness(Witness::Signature(env::predecessor_account_id()));
+        self.transactions.insert(&tx_name, &tx);
+    }
+
+    // the current block time satisfies any Timestamp condition in the plan
+    pub fn apply_timestamp_witness(&mut self, tx_name: String) {
+        let mut tx = self.transactions.get(&tx_name).unwrap();
+        tx.apply_witness(Witness::Timestamp(env::block_timestamp()));
+        self.transactions.insert(&tx_name, &tx);
+    }
+
+    // NEP-141 receiver hook: called by the token contract itself when a sender
+    // does ft_transfer_call into this escrow with msg naming the target transaction
+    pub fn ft_on_transfer(&mut self, sender_id: AccountId, amount: U128, msg: String) -> PromiseOrValue<U128> {
+        assert!(!self.cancelled, "Escrow has been cancelled");
         self.assert_agreement();
+        let tx_name = msg;
+        let token_id = env::predecessor_account_id();
         assert!(self.transactions.get(&tx_name).is_some(), "Transaction not in contract");
         let mut tx = self.transactions.get(&tx_name).unwrap();
-        assert!(!&tx.satisfied, "Transaction has deposit already");
-        assert_eq!(&tx.asset.name, &asset.name, "Asset being deposited does not match asset needed");
-        assert_eq!(&tx.asset.quantity, &asset.quantity, 
-            "{} needed, {} deposited", &tx.asset.quantity, &asset.quantity);
-        let curr_user = env::current_account_id();
-        assert_eq!(&tx.sender, &curr_user, "Asset needed from {}, not {}", &tx.sender, &curr_user);
-        self.assets.insert(&curr_user, &asset);
-        tx.toggle_satisfied();
+        assert!(!tx.deposited, "Transaction has deposit already");
+        match &tx.asset.kind {
+            AssetKind::Fungible { token_id: expected_token, amount: expected_amount } => {
+                assert_eq!(expected_token, &token_id, "Asset being deposited does not match asset needed");
+                assert_eq!(expected_amount.0, amount.0, "{} needed, {} deposited", expected_amount.0, amount.0);
+            },
+            AssetKind::NonFungible { .. } => env::panic("Transaction expects an NFT, not a fungible token".as_bytes())
+        }
+        assert_eq!(&tx.sender, &sender_id, "Asset needed from {}, not {}", &tx.sender, &sender_id);
+        tx.toggle_deposited();
+        self.transactions.insert(&tx_name, &tx);
+        PromiseOrValue::Value(U128(0))
+    }
+
+    // NEP-171 receiver hook: called by the token contract itself when a sender
+    // does nft_transfer_call into this escrow with msg naming the target transaction
+    pub fn nft_on_transfer(&mut self, sender_id: AccountId, _previous_owner_id: AccountId, token_id: String, msg: String) -> bool {
+        assert!(!self.cancelled, "Escrow has been cancelled");
+        self.assert_agreement();
+        let tx_name = msg;
+        let nft_contract = env::predecessor_account_id();
+        assert!(self.transactions.get(&tx_name).is_some(), "Transaction not in contract");
+        let mut tx = self.transactions.get(&tx_name).unwrap();
+        assert!(!tx.deposited, "Transaction has deposit already");
+        match &tx.asset.kind {
+            AssetKind::NonFungible { nft_contract: expected_contract, token_id: expected_token } => {
+                assert_eq!(expected_contract, &nft_contract, "Asset being deposited does not match asset needed");
+                assert_eq!(expected_token, &token_id, "{} needed, {} deposited", expected_token, token_id);
+            },
+            AssetKind::Fungible { .. } => env::panic("Transaction expects a fungible token, not an NFT".as_bytes())
+        }
+        assert_eq!(&tx.sender, &sender_id, "Asset needed from {}, not {}", &tx.sender, &sender_id);
+        tx.toggle_deposited();
+        self.transactions.insert(&tx_name, &tx);
+        false
     }
 
     // option available if all owners agree but one party does not deposit within reasonable time
     pub fn withdraw_asset(&mut self, tx_name: String) {
-        // TODO: cross contract to senders tokens to see if they got it
+        assert!(!self.cancelled, "Escrow has been cancelled");
         assert!(self.transactions.get(&tx_name).is_some(), "Transaction not in contract");
         let mut tx = self.transactions.get(&tx_name).unwrap();
-        let curr_user = env::current_account_id();
+        let curr_user = env::predecessor_account_id();
         assert_eq!(&tx.sender, &curr_user, "Asset needed from {}, not {}", &tx.sender, &curr_user);
-        self.assets.remove(&curr_user);
-        // TODO: send to user
-        tx.toggle_satisfied();
+        assert!(tx.deposited, "Nothing deposited for this transaction");
+        tx.toggle_deposited();
+        self.transactions.insert(&tx_name, &tx);
+        tx.asset.kind.transfer(curr_user);
     }
 
     pub fn sign(&mut self) {
         self.assert_owner();
-        let curr_user = env::current_account_id();
-        self.signatures.insert(&curr_user); 
+        let curr_user = env::predecessor_account_id();
+        self.signatures.insert(&curr_user);
+    }
+
+    // lets an owner withdraw their approval, even after the threshold has been reached
+    pub fn revoke_signature(&mut self) {
+        self.assert_owner();
+        let curr_user = env::predecessor_account_id();
+        self.signatures.remove(&curr_user);
+    }
+
+    pub fn get_approval_status(&self) -> ApprovalStatus {
+        ApprovalStatus {
+            signed: self.signatures.len() as u32,
+            threshold: self.threshold
+        }
+    }
+
+    // any sender (or owner) may call this once their transaction's deadline has
+    // passed and the escrow has not fully executed; every already-deposited
+    // asset is returned to its depositor and the escrow is cancelled for good
+    pub fn refund(&mut self, tx_name: String) {
+        assert!(!self.cancelled, "Escrow has already been cancelled");
+        // a transaction that has been paid out is removed by on_transfer_complete,
+        // so an already-executed one fails this lookup
+        assert!(self.transactions.get(&tx_name).is_some(), "Transaction not in contract");
+        let tx = self.transactions.get(&tx_name).unwrap();
+        assert!(env::block_timestamp() > tx.deadline, "Deadline has not passed yet");
+        let curr_user = env::predecessor_account_id();
+        assert!(curr_user == tx.sender || self.owners.contains(&curr_user),
+            "only the sender or an owner can trigger a refund");
+        // every already-deposited leg of the escrow gets refunded, not just this one
+        let deposited_txs: Vec<String> = self.transactions.iter()
+            .filter(|(_, tx)| tx.deposited)
+            .map(|(name, _)| name)
+            .collect();
+        for tx_name in deposited_txs {
+            let mut tx = self.transactions.get(&tx_name).unwrap();
+            tx.asset.kind.transfer(tx.sender.clone());
+            tx.toggle_deposited();
+            self.transactions.insert(&tx_name, &tx);
+        }
+        self.cancelled = true;
     }
 
     pub fn execute(&mut self) {
-        self.assert_agreement();
-        self.assert_txs_satisfied();
-        for tx in self.transactions.iter() {
-            // send asset to other contract
-            println!("Giving {} {}s to {}", tx.1.asset.name, tx.1.asset.quantity, tx.1.receiver);
+        assert!(!self.cancelled, "Escrow has been cancelled");
+        self.assert_txs_executable();
+        for (tx_name, tx) in self.transactions.iter() {
+            if tx.is_payable() {
+                let payment = tx.plan.payment().unwrap();
+                tx.asset.kind.transfer(payment.receiver.clone())
+                    .then(ext_self::on_transfer_complete(tx_name, env::current_account_id(), 0, GAS_FOR_TRANSFER_RESOLVE));
+            }
+        }
+    }
+
+    #[private]
+    pub fn on_transfer_complete(&mut self, tx_name: String) -> bool {
+        let transferred = is_promise_success();
+        if transferred {
+            self.transactions.remove(&tx_name);
         }
-        self.assets.clear();
+        transferred
     }
 }
 
 impl Contract {
     fn assert_owner(&self) {
-        let curr_user = env::current_account_id();
+        let curr_user = env::predecessor_account_id();
         assert!(self.owners.contains(&curr_user), "only callable by owner");
     }
 
     fn assert_agreement(&self) {
-        for owner in self.owners.iter() {
-            assert!(self.signatures.contains(&owner), "Not all owners have agreed upon the terms");
-        }
+        assert!(self.signatures.len() as u32 >= self.threshold,
+            "Not enough owners have agreed upon the terms ({} of {} needed)", self.signatures.len(), self.threshold);
     }
 
     fn assert_no_agreement(&self) {
-        for owner in self.owners.iter() {
-            assert!(!self.signatures.contains(&owner), "Owners have already agreed upon the terms");
-        }
+        assert!(self.signatures.is_empty(), "Owners have already agreed upon the terms");
     }
 
-    fn assert_txs_satisfied(&self) {
+    fn assert_txs_executable(&self) {
+        let now = env::block_timestamp();
         for tx in self.transactions.iter() {
-            assert!(tx.1.satisfied, "Cannot execute transaction, {} must deposit {} {}s", 
-                tx.1.sender, tx.1.asset.name, tx.1.asset.quantity);
+            if now > tx.1.deadline {
+                assert!(tx.1.is_payable(), "Deadline has passed without {}'s release conditions for {} being met and deposited, call refund instead",
+                    tx.1.sender, tx.1.asset.kind.describe());
+            } else {
+                assert!(tx.1.is_payable(), "Cannot execute transaction, release conditions for {}'s {} are not yet met and deposited",
+                    tx.1.sender, tx.1.asset.kind.describe());
+            }
         }
     }
 }
@@ -190,12 +441,15 @@ mod tests {
     }
 
 
-    fn get_context(input: Vec<u8>, is_view: bool, sender: AccountId) -> VMContext {
+    // `current_account_id` is always the contract's own account; `caller` is whoever
+    // is actually invoking the method, and must land in `predecessor_account_id` so
+    // that owner/signer checks can't be satisfied by mistaking one for the other
+    fn get_context(input: Vec<u8>, is_view: bool, caller: AccountId) -> VMContext {
         VMContext {
-            current_account_id: sender,
-            signer_account_id: "bigpoopoo96.testnet".to_string(),
+            current_account_id: "escrow.testnet".to_string(),
+            signer_account_id: caller.clone(),
             signer_account_pk: vec![0, 1, 2],
-            predecessor_account_id: "bigpoopoo96.testnet".to_string(),
+            predecessor_account_id: caller,
             input,
             block_index: 0,
             block_timestamp: 0,
@@ -211,21 +465,28 @@ mod tests {
         }
     }
 
+    fn release_to_bigpoopoo() -> ReleasePlan {
+        ReleasePlan::Pay(Payment { receiver: bigpoopoo96() })
+    }
+
+    fn fungible_poop() -> AssetKind {
+        AssetKind::Fungible { token_id: "poop-token.testnet".to_string(), amount: U128(4) }
+    }
+
+    fn nonfungible_doggo() -> AssetKind {
+        AssetKind::NonFungible { nft_contract: "doggo-nft.testnet".to_string(), token_id: "doggo#1".to_string() }
+    }
+
     #[test]
     fn add_tx() {
         // set up the mock context into the testing environment
         let context = get_context(vec![], false, bigpeepee69());
         testing_env!(context);
-        let ass = Asset {
-            name: "poop".to_string(),
-            quantity: 4.0
-        };
-        let tx = Transaction::new(bigpeepee69(), 
-            bigpoopoo96(), 
-            ass);
+        let ass = Asset { kind: fungible_poop() };
+        let tx = Transaction::new(bigpeepee69(), ass, release_to_bigpoopoo(), 1_000);
         // instantiate a contract variable with the counter at zero
-        let mut contract = Contract::new([bigpeepee69()].to_vec());
-        contract.add_tx("shit trade".to_string(), bigpeepee69(), bigpoopoo96(), "poop".to_string(), 4.0);
+        let mut contract = Contract::new([bigpeepee69()].to_vec(), 1);
+        contract.add_tx("shit trade".to_string(), bigpeepee69(), fungible_poop(), release_to_bigpoopoo(), 1_000);
         let same_tx = contract.get_tx("shit trade".to_string());
         assert!(same_tx == tx);
     }
@@ -235,19 +496,164 @@ mod tests {
         // set up the mock context into the testing environment
         let context = get_context(vec![], false, bigpeepee69());
         testing_env!(context);
-        let ass = Asset {
-            name: "poop".to_string(),
-            quantity: 4.0
-        };
-        let tx = Transaction::new(bigpeepee69(), 
-            bigpoopoo96(), 
-            ass);
+        let ass = Asset { kind: fungible_poop() };
+        let tx = Transaction::new(bigpeepee69(), ass, release_to_bigpoopoo(), 1_000);
         // instantiate a contract variable with the counter at zero
-        let mut contract = Contract::new([bigpeepee69()].to_vec());
-        contract.add_tx("shit trade".to_string(), bigpeepee69(), bigpoopoo96(), "poop".to_string(), 4.0);
+        let mut contract = Contract::new([bigpeepee69()].to_vec(), 1);
+        contract.add_tx("shit trade".to_string(), bigpeepee69(), fungible_poop(), release_to_bigpoopoo(), 1_000);
         let same_tx = contract.get_tx("shit trade".to_string());
         assert!(same_tx == tx);
         contract.rm_tx( "shit trade".to_string());
         assert!(contract.transactions.get(&"shit trade".to_string()).is_none());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn apply_witness_settles_deadline_plan() {
+        let context = get_context(vec![], false, bigpeepee69());
+        testing_env!(context);
+        let ass = Asset { kind: fungible_poop() };
+        let plan = ReleasePlan::After(Condition::Timestamp(100), Box::new(release_to_bigpoopoo()));
+        let mut tx = Transaction::new(bigpeepee69(), ass, plan, 1_000);
+        assert!(!tx.is_executable());
+        tx.apply_witness(Witness::Timestamp(50));
+        assert!(!tx.is_executable());
+        tx.apply_witness(Witness::Timestamp(100));
+        assert!(tx.is_executable());
+    }
+
+    #[test]
+    fn apply_signature_witness_uses_caller_identity() {
+        let context = get_context(vec![], false, bigpeepee69());
+        testing_env!(context);
+        let mut contract = Contract::new([bigpeepee69()].to_vec(), 1);
+        let plan = ReleasePlan::After(Condition::Signature(bigpoopoo96()), Box::new(release_to_bigpoopoo()));
+        contract.add_tx("shit trade".to_string(), bigpeepee69(), fungible_poop(), plan, 1_000);
+
+        // an impostor calling in can't satisfy a condition naming someone else
+        testing_env!(get_context(vec![], false, "impostor.testnet".to_string()));
+        contract.apply_signature_witness("shit trade".to_string());
+        assert!(!contract.get_tx("shit trade".to_string()).is_executable());
+
+        // only the named signer, calling in as themselves, satisfies it
+        testing_env!(get_context(vec![], false, bigpoopoo96()));
+        contract.apply_signature_witness("shit trade".to_string());
+        assert!(contract.get_tx("shit trade".to_string()).is_executable());
+    }
+
+    #[test]
+    fn execute_pays_out_only_after_deposit() {
+        let context = get_context(vec![], false, bigpeepee69());
+        testing_env!(context);
+        let mut contract = Contract::new([bigpeepee69()].to_vec(), 1);
+        let plan = ReleasePlan::After(Condition::Timestamp(100), Box::new(release_to_bigpoopoo()));
+        contract.add_tx("shit trade".to_string(), bigpeepee69(), fungible_poop(), plan, 1_000);
+        contract.sign();
+
+        testing_env!(get_context(vec![], false, "poop-token.testnet".to_string()));
+        contract.ft_on_transfer(bigpeepee69(), U128(4), "shit trade".to_string());
+
+        let mut context = get_context(vec![], false, bigpeepee69());
+        context.block_timestamp = 100;
+        testing_env!(context);
+        contract.apply_timestamp_witness("shit trade".to_string());
+        assert!(contract.get_tx("shit trade".to_string()).is_payable());
+        // does not panic: the transaction is both settled and deposited
+        contract.execute();
+    }
+
+    #[test]
+    #[should_panic(expected = "are not yet met and deposited")]
+    fn execute_rejects_undeposited_settled_tx() {
+        let context = get_context(vec![], false, bigpeepee69());
+        testing_env!(context);
+        let mut contract = Contract::new([bigpeepee69()].to_vec(), 1);
+        let plan = ReleasePlan::After(Condition::Timestamp(100), Box::new(release_to_bigpoopoo()));
+        contract.add_tx("shit trade".to_string(), bigpeepee69(), fungible_poop(), plan, 1_000);
+        contract.sign();
+
+        let mut context = get_context(vec![], false, bigpeepee69());
+        context.block_timestamp = 100;
+        testing_env!(context);
+        contract.apply_timestamp_witness("shit trade".to_string());
+        assert!(contract.get_tx("shit trade".to_string()).is_executable());
+        // settled but never deposited into escrow - must not pay out
+        contract.execute();
+    }
+
+    #[test]
+    fn refund_after_deadline_cancels_escrow() {
+        let context = get_context(vec![], false, bigpeepee69());
+        testing_env!(context);
+        let mut contract = Contract::new([bigpeepee69()].to_vec(), 1);
+        contract.add_tx("shit trade".to_string(), bigpeepee69(), fungible_poop(), release_to_bigpoopoo(), 100);
+
+        let mut context = get_context(vec![], false, bigpeepee69());
+        context.block_timestamp = 200;
+        testing_env!(context);
+        contract.refund("shit trade".to_string());
+        assert!(contract.cancelled);
+    }
+
+    #[test]
+    fn nft_on_transfer_accepts_matching_deposit() {
+        let context = get_context(vec![], false, bigpeepee69());
+        testing_env!(context);
+        let mut contract = Contract::new([bigpeepee69()].to_vec(), 1);
+        contract.add_tx("doggo trade".to_string(), bigpeepee69(), nonfungible_doggo(), release_to_bigpoopoo(), 1_000);
+        contract.sign();
+
+        testing_env!(get_context(vec![], false, "doggo-nft.testnet".to_string()));
+        let return_token = contract.nft_on_transfer(bigpeepee69(), bigpeepee69(), "doggo#1".to_string(), "doggo trade".to_string());
+        assert!(!return_token);
+        let tx = contract.get_tx("doggo trade".to_string());
+        assert!(tx.deposited);
+        assert!(tx.asset.kind == nonfungible_doggo());
+    }
+
+    #[test]
+    #[should_panic(expected = "doggo#1 needed, wrong-token deposited")]
+    fn nft_on_transfer_rejects_mismatched_token_id() {
+        let context = get_context(vec![], false, bigpeepee69());
+        testing_env!(context);
+        let mut contract = Contract::new([bigpeepee69()].to_vec(), 1);
+        contract.add_tx("doggo trade".to_string(), bigpeepee69(), nonfungible_doggo(), release_to_bigpoopoo(), 1_000);
+        contract.sign();
+
+        testing_env!(get_context(vec![], false, "doggo-nft.testnet".to_string()));
+        contract.nft_on_transfer(bigpeepee69(), bigpeepee69(), "wrong-token".to_string(), "doggo trade".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Transaction expects an NFT, not a fungible token")]
+    fn ft_on_transfer_rejects_wrong_asset_kind() {
+        let context = get_context(vec![], false, bigpeepee69());
+        testing_env!(context);
+        let mut contract = Contract::new([bigpeepee69()].to_vec(), 1);
+        contract.add_tx("doggo trade".to_string(), bigpeepee69(), nonfungible_doggo(), release_to_bigpoopoo(), 1_000);
+        contract.sign();
+
+        testing_env!(get_context(vec![], false, "doggo-nft.testnet".to_string()));
+        contract.ft_on_transfer(bigpeepee69(), U128(4), "doggo trade".to_string());
+    }
+
+    #[test]
+    fn threshold_approval_requires_m_of_n() {
+        let context = get_context(vec![], false, bigpeepee69());
+        testing_env!(context);
+        let owners = vec![bigpeepee69(), bigpoopoo96(), "thirdowner.testnet".to_string()];
+        let mut contract = Contract::new(owners, 2);
+        assert!(contract.get_approval_status() == ApprovalStatus { signed: 0, threshold: 2 });
+
+        testing_env!(get_context(vec![], false, bigpeepee69()));
+        contract.sign();
+        assert!(contract.get_approval_status() == ApprovalStatus { signed: 1, threshold: 2 });
+
+        testing_env!(get_context(vec![], false, bigpoopoo96()));
+        contract.sign();
+        assert!(contract.get_approval_status() == ApprovalStatus { signed: 2, threshold: 2 });
+
+        testing_env!(get_context(vec![], false, bigpeepee69()));
+        contract.revoke_signature();
+        assert!(contract.get_approval_status() == ApprovalStatus { signed: 1, threshold: 2 });
+    }
+}